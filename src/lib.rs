@@ -1,12 +1,14 @@
 // mammon - a storage engine
-// `store(key: ToString, val: Iter<u8>): Result<()>` and `retrieve(key: ToString): Result<Iter<u8>>` and maybe `defragement(): Result<>`
+// `store(key: ToString, val: Iter<u8>): Result<()>` and `retrieve(key: ToString): Result<Iter<u8>>` and `defragment(): Result<()>`
 
 use std::collections::HashMap;
 use std::fs::{create_dir_all, File, OpenOptions};
 use std::io::{Read, Seek, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, Result};
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
@@ -15,12 +17,132 @@ pub struct Index {
     pub length: u64,
 }
 
+/// distinguishes damage to the store itself from ordinary "key not found" misses, so
+/// callers can tell the two apart instead of matching on an opaque error string.
+#[derive(thiserror::Error, Debug)]
+pub enum StoreError {
+    #[error("blob for key {key:?} is corrupt: index points at offset {offset} length {length}, which is out of bounds or truncated")]
+    BlobCorrupt { key: String, offset: u64, length: u64 },
+}
+
+/// a single mutation to `indexes`, as appended to `mammon.wal`. replaying these in order on
+/// top of the last `mammon.cbor` checkpoint reconstructs the full map without having to
+/// rewrite it on every `store`/`delete`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum WalRecord {
+    Put { key: String, offset: u64, length: u64 },
+    Del { key: String },
+}
+
+/// when a free-list slot is reused for a smaller blob, a leftover residual shorter than this
+/// is left in place rather than tracked, to avoid fragmenting the store with slivers too
+/// small to ever be reused. regions freed by `delete` are tracked whatever their length.
+const MIN_FRAGMENT_LENGTH: u64 = 32;
+
+/// size of the buffer used to copy blobs during `defragment`, so large blobs don't
+/// need to be held in memory all at once.
+const DEFRAGMENT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// blobs larger than this are content-defined-chunked instead of stored whole, so a small
+/// edit only re-stores the chunks it actually touches. see `store_chunked`.
+const CDC_CHUNK_THRESHOLD: usize = CDC_MAX_CHUNK_SIZE;
+const CDC_MIN_CHUNK_SIZE: usize = 2 * 1024;
+const CDC_MAX_CHUNK_SIZE: usize = 64 * 1024;
+const CDC_TARGET_CHUNK_SIZE: usize = 8 * 1024;
+/// mask applied to the rolling fingerprint; a boundary is cut whenever the masked bits are
+/// all zero, which happens on average once every `CDC_TARGET_CHUNK_SIZE` bytes.
+const CDC_BOUNDARY_MASK: u64 = (CDC_TARGET_CHUNK_SIZE - 1) as u64;
+/// width of the sliding window the rolling hash is computed over.
+const CDC_WINDOW_SIZE: usize = 48;
+
+/// deterministic pseudo-random table for the buzhash rolling hash, generated once via
+/// splitmix64 rather than hardcoded, so each byte value maps to an independent 64-bit mask.
+fn buzhash_table() -> &'static [u64; 256] {
+    static TABLE: std::sync::OnceLock<[u64; 256]> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0u64; 256];
+        let mut seed: u64 = 0x9E3779B97F4A7C15;
+        for slot in table.iter_mut() {
+            seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            *slot = z ^ (z >> 31);
+        }
+        table
+    })
+}
+
+/// splits `data` into content-defined chunks using a buzhash rolling hash over a
+/// `CDC_WINDOW_SIZE`-byte window: a boundary is cut whenever the fingerprint's low bits are
+/// all zero, bounded to `[CDC_MIN_CHUNK_SIZE, CDC_MAX_CHUNK_SIZE]` (except the final chunk,
+/// which holds whatever is left and may be shorter than the minimum). returns the exclusive end
+/// offset of each chunk. inserting or changing bytes only shifts boundaries locally, so
+/// unaffected chunks keep their hash and don't need to be re-stored.
+fn chunk_boundaries(data: &[u8]) -> Vec<usize> {
+    let table = buzhash_table();
+    let mut boundaries = Vec::new();
+    let mut fingerprint: u64 = 0;
+    let mut chunk_start = 0usize;
+
+    for (i, &byte) in data.iter().enumerate() {
+        fingerprint = fingerprint.rotate_left(1) ^ table[byte as usize];
+
+        if i >= CDC_WINDOW_SIZE {
+            let leaving = data[i - CDC_WINDOW_SIZE];
+            fingerprint ^= table[leaving as usize].rotate_left((CDC_WINDOW_SIZE % 64) as u32);
+        }
+
+        let chunk_len = i + 1 - chunk_start;
+        if chunk_len < CDC_MIN_CHUNK_SIZE {
+            continue;
+        }
+
+        if chunk_len >= CDC_MAX_CHUNK_SIZE || fingerprint & CDC_BOUNDARY_MASK == 0 {
+            boundaries.push(i + 1);
+            chunk_start = i + 1;
+        }
+    }
+
+    if chunk_start < data.len() {
+        boundaries.push(data.len());
+    }
+
+    boundaries
+}
+
+lazy_static::lazy_static! {
+    /// process-wide map from canonicalized store directory to its shared handle, so that
+    /// repeated `open_shared` calls for the same directory converge on one synchronized
+    /// `Store` instead of opening independent file handles with diverging in-memory state.
+    static ref DATASTORE_MAP: Mutex<HashMap<PathBuf, Arc<Mutex<Store>>>> = Mutex::new(HashMap::new());
+}
+
 pub struct Store {
+    pub directory: PathBuf,
     pub indexes: HashMap<String, Index>,
     pub empties: Vec<Index>,
     pub blob_file: File,
     pub empty_file: File,
     pub db_file: File,
+    /// append-only log of `WalRecord`s backing `indexes`; see `append_wal`/`checkpoint`.
+    pub wal_file: File,
+    /// content-hash -> (location, refcount), populated only when dedup is enabled via
+    /// `new_deduped`/`open_deduped`, or by `open_shared` on a deduped directory. see
+    /// `store_deduped`.
+    pub chunk_index: Option<HashMap<[u8; 32], (Index, u64)>>,
+    /// key -> content-hash, the other half of the dedup bookkeeping; lets `delete` find
+    /// the chunk a key's refcount belongs to.
+    pub key_hashes: Option<HashMap<String, [u8; 32]>>,
+    /// key -> ordered chunk hashes, for blobs large enough to go through
+    /// `store_chunked`/`retrieve_chunked` instead of being stored whole.
+    pub manifests: Option<HashMap<String, Vec<[u8; 32]>>>,
+    chunk_file: Option<File>,
+    key_hash_file: Option<File>,
+    manifest_file: Option<File>,
+    /// advisory lock on the store directory, held for the lifetime of the `Store`; dropping
+    /// it (with the rest of `Store`) releases the lock. see `acquire_lock`.
+    _lock_file: File,
 }
 /// open a file with r/w permissions.
 fn open_file<P: AsRef<Path>>(path: P) -> std::io::Result<File> {
@@ -40,6 +162,78 @@ fn create_file<P: AsRef<Path>>(path: P) -> std::io::Result<File> {
         .open(path.as_ref())
 }
 
+/// open (creating if absent) a file with r/w permissions, without truncating existing
+/// content. used for the lock file, where content doesn't matter but truncating a file held
+/// open by another process is needless churn.
+fn open_lock_file<P: AsRef<Path>>(path: P) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path.as_ref())
+}
+
+/// takes a non-blocking advisory (flock) lock on `mammon.lock` in the store directory, so a
+/// second process opening the same directory fails fast with a clear error instead of
+/// silently racing the first over the blob file and CBOR sidecars.
+fn acquire_lock(directory: &Path) -> Result<File> {
+    let lock_file = open_lock_file(directory.join("mammon.lock"))?;
+
+    lock_file
+        .try_lock_exclusive()
+        .map_err(|_| anyhow::anyhow!("store already open: {:?}", directory))?;
+
+    Ok(lock_file)
+}
+
+/// decodes a single CBOR document from `file`, treating an empty file as `T::default()`
+/// rather than an `UnexpectedEof` error. a sidecar is empty whenever it's been `create_file`d
+/// but nothing has been checkpointed to it yet (e.g. a store that's only ever gone through
+/// `store`/`delete`, which persist via the WAL, not `mammon.cbor` itself) — that's the normal
+/// state of a freshly-created-but-never-checkpointed store, not corruption.
+fn read_cbor_or_default<T: serde::de::DeserializeOwned + Default>(file: &File) -> Result<T> {
+    if file.metadata()?.len() == 0 {
+        return Ok(T::default());
+    }
+    Ok(ciborium::from_reader(file)?)
+}
+
+/// truncates `file` and writes `value` as its sole CBOR document, overwriting whatever was
+/// there before. every sidecar file except the WAL (which is deliberately append-only, see
+/// `append_wal`) holds a single up-to-date document and must go through this rather than a
+/// bare `ciborium::into_writer`, or repeated writes pile up stale documents and only the
+/// first one ever decodes back.
+fn rewrite_cbor<T: Serialize>(file: &File, value: &T) -> Result<()> {
+    file.set_len(0)?;
+    let mut writer = file;
+    writer.seek(std::io::SeekFrom::Start(0))?;
+    ciborium::into_writer(value, writer)?;
+    Ok(())
+}
+
+/// rebuilds `indexes` on top of a checkpoint by replaying WAL records in order. stops at the
+/// first record that fails to decode rather than erroring, since a torn trailing record just
+/// means the write it belongs to never completed, and everything before it is still valid.
+fn replay_wal(wal_file: &File, indexes: &mut HashMap<String, Index>) -> Result<()> {
+    let mut reader = wal_file;
+    reader.seek(std::io::SeekFrom::Start(0))?;
+
+    loop {
+        match ciborium::from_reader::<WalRecord, _>(&mut reader) {
+            Ok(WalRecord::Put { key, offset, length }) => {
+                indexes.insert(key, Index { offset, length });
+            }
+            Ok(WalRecord::Del { key }) => {
+                indexes.remove(&key);
+            }
+            Err(_) => break,
+        }
+    }
+
+    Ok(())
+}
+
 impl Store {
     /// creates a *new* Mammon::Store in the given directory. cannot be used to open an existing store.
     pub fn new(directory: PathBuf) -> Result<Self> {
@@ -49,89 +243,740 @@ impl Store {
             bail!("{:?} is not a directory", directory);
         }
 
+        let lock_file = acquire_lock(&directory)?;
+
         let blob_file = create_file(directory.join("mammon_blobs.bin"))?;
         let empty_file = create_file(directory.join("mammon_empties.cbor"))?;
         let db_file = create_file(directory.join("mammon.cbor"))?;
+        let wal_file = create_file(directory.join("mammon.wal"))?;
 
         Ok(Self {
+            directory,
             indexes: HashMap::new(),
             empties: vec![],
             blob_file,
             empty_file,
             db_file,
+            wal_file,
+            chunk_index: None,
+            key_hashes: None,
+            manifests: None,
+            chunk_file: None,
+            key_hash_file: None,
+            manifest_file: None,
+            _lock_file: lock_file,
         })
     }
+
+    /// like `new`, but opts into content-addressed deduplication: see `store_deduped`.
+    pub fn new_deduped(directory: PathBuf) -> Result<Self> {
+        let mut store = Self::new(directory)?;
+        store.enable_dedup()?;
+        Ok(store)
+    }
+
     /// opens an existing Mammon::Store in a given directory. cannot be used to create a new store.
     pub fn open(directory: PathBuf) -> Result<Self> {
         if !directory.exists() {
             bail!("{:?} does not exist", directory);
         }
 
+        let lock_file = acquire_lock(&directory)?;
+
         let blob_file = open_file(directory.join("mammon_blobs.bin"))?;
         let empty_file = open_file(directory.join("mammon_empties.cbor"))?;
         let db_file = open_file(directory.join("mammon.cbor"))?;
+        let wal_file = open_file(directory.join("mammon.wal"))?;
 
-        let indexes: HashMap<String, Index> = ciborium::from_reader(&db_file)?;
-        let empties: Vec<Index> = ciborium::from_reader(&empty_file)?;
+        let mut indexes: HashMap<String, Index> = read_cbor_or_default(&db_file)?;
+        let empties: Vec<Index> = read_cbor_or_default(&empty_file)?;
+
+        replay_wal(&wal_file, &mut indexes)?;
 
         Ok(Self {
+            directory,
             indexes,
             empties,
             blob_file,
             empty_file,
             db_file,
+            wal_file,
+            chunk_index: None,
+            key_hashes: None,
+            manifests: None,
+            chunk_file: None,
+            key_hash_file: None,
+            manifest_file: None,
+            _lock_file: lock_file,
         })
     }
 
+    /// opens (or creates) the store at `directory` and returns a handle shared across every
+    /// `open_shared` call for this directory within the current process, so concurrent
+    /// callers synchronize through one `Store` instead of duplicating file descriptors and
+    /// diverging in-memory indexes. see `DATASTORE_MAP`.
+    pub fn open_shared(directory: PathBuf) -> Result<Arc<Mutex<Self>>> {
+        if !directory.exists() {
+            create_dir_all(&directory)?;
+        }
+        let canonical = directory.canonicalize()?;
+
+        let mut registry = DATASTORE_MAP.lock().unwrap();
+        if let Some(store) = registry.get(&canonical) {
+            return Ok(Arc::clone(store));
+        }
+
+        let mut store = if canonical.join("mammon.cbor").exists() {
+            Self::open(canonical.clone())?
+        } else {
+            Self::new(canonical.clone())?
+        };
+
+        // a directory created by `new_deduped` carries `mammon_chunks.cbor`; load its dedup
+        // sidecars too, or `store`/`get`/`delete` would silently take the non-dedup path over
+        // a deduped store and corrupt it (chunked keys unreadable, refcounts untouched).
+        if canonical.join("mammon_chunks.cbor").exists() {
+            store.load_dedup()?;
+        }
+
+        let store = Arc::new(Mutex::new(store));
+        registry.insert(canonical, Arc::clone(&store));
+
+        Ok(store)
+    }
+
+    /// like `open`, for a store created with `new_deduped`.
+    pub fn open_deduped(directory: PathBuf) -> Result<Self> {
+        let mut store = Self::open(directory)?;
+        store.load_dedup()?;
+        Ok(store)
+    }
+
+    fn enable_dedup(&mut self) -> Result<()> {
+        self.chunk_file = Some(create_file(self.directory.join("mammon_chunks.cbor"))?);
+        self.key_hash_file = Some(create_file(self.directory.join("mammon_key_hashes.cbor"))?);
+        self.manifest_file = Some(create_file(self.directory.join("mammon_manifests.cbor"))?);
+        self.chunk_index = Some(HashMap::new());
+        self.key_hashes = Some(HashMap::new());
+        self.manifests = Some(HashMap::new());
+
+        Ok(())
+    }
+
+    fn load_dedup(&mut self) -> Result<()> {
+        let chunk_file = open_file(self.directory.join("mammon_chunks.cbor"))?;
+        let key_hash_file = open_file(self.directory.join("mammon_key_hashes.cbor"))?;
+        let manifest_file = open_file(self.directory.join("mammon_manifests.cbor"))?;
+
+        self.chunk_index = Some(read_cbor_or_default(&chunk_file)?);
+        self.key_hashes = Some(read_cbor_or_default(&key_hash_file)?);
+        self.manifests = Some(read_cbor_or_default(&manifest_file)?);
+        self.chunk_file = Some(chunk_file);
+        self.key_hash_file = Some(key_hash_file);
+        self.manifest_file = Some(manifest_file);
+
+        Ok(())
+    }
+
     /// store a blob in the store, returning Ok(()) on success
     pub fn store(&mut self, key: impl ToString, val: Vec<u8>) -> Result<()> {
-        let offset = self.blob_file.seek(std::io::SeekFrom::End(0))?;
-        let length = val.len() as u64;
+        if self.chunk_index.is_some() {
+            return self.store_deduped(key, val);
+        }
+
+        let index = self.write_blob(&val)?;
+        let key = key.to_string();
 
-        self.blob_file.write_all(val.as_slice())?;
+        self.indexes.insert(key.clone(), index);
 
-        self.indexes
-            .insert(key.to_string(), Index { offset, length });
+        self.append_wal(&WalRecord::Put {
+            key,
+            offset: index.offset,
+            length: index.length,
+        })?;
 
+        Ok(())
+    }
+
+    /// append a record to `mammon.wal` and fsync it, so a crash right after `store`/`delete`
+    /// returns never loses the mutation it just acknowledged.
+    fn append_wal(&mut self, record: &WalRecord) -> Result<()> {
+        self.wal_file.seek(std::io::SeekFrom::End(0))?;
+        ciborium::into_writer(record, &self.wal_file)?;
+        self.wal_file.sync_all()?;
+
+        Ok(())
+    }
+
+    /// writes the full `indexes` map to `mammon.cbor` and truncates the WAL, so the next
+    /// `open()` starts replay from this snapshot instead of the whole mutation history.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.db_file.set_len(0)?;
+        self.db_file.seek(std::io::SeekFrom::Start(0))?;
         ciborium::into_writer(&self.indexes, &self.db_file)?;
+        self.db_file.sync_all()?;
+
+        self.wal_file.set_len(0)?;
+        self.wal_file.seek(std::io::SeekFrom::Start(0))?;
+        self.wal_file.sync_all()?;
+
+        Ok(())
+    }
+
+    /// releases `key`'s existing deduped content, if any, from the refcount it holds:
+    /// decrements every chunk hash it references (a single hash for a `store_deduped` key, a
+    /// manifest's worth for a `store_chunked` one) and reclaims a chunk's blob region into
+    /// `empties` once nothing references it anymore. a no-op if `key` has no prior content,
+    /// so `store_deduped`/`store_chunked` can call it unconditionally before rebinding `key`
+    /// to new content, rather than leaking the old content's refcount on every overwrite.
+    fn release_dedup_refs(&mut self, key: &str) {
+        let hashes: Vec<[u8; 32]> = if let Some(manifest) = self.manifests.as_mut().unwrap().remove(key) {
+            manifest
+        } else if let Some(hash) = self.key_hashes.as_mut().unwrap().remove(key) {
+            vec![hash]
+        } else {
+            return;
+        };
+
+        let chunk_index = self.chunk_index.as_mut().unwrap();
+        for hash in hashes {
+            if let Some((index, refcount)) = chunk_index.get_mut(&hash) {
+                *refcount -= 1;
+                if *refcount == 0 {
+                    self.empties.push(*index);
+                    chunk_index.remove(&hash);
+                }
+            }
+        }
+    }
+
+    /// content-addressed counterpart to `store`: identical payloads are written once and
+    /// shared between keys via a reference count, see `chunk_index`. blobs over
+    /// `CDC_CHUNK_THRESHOLD` are delegated to `store_chunked` instead.
+    fn store_deduped(&mut self, key: impl ToString, val: Vec<u8>) -> Result<()> {
+        if val.len() > CDC_CHUNK_THRESHOLD {
+            return self.store_chunked(key, &val);
+        }
+
+        let hash = *blake3::hash(&val).as_bytes();
+
+        let index = if self.chunk_index.as_ref().unwrap().contains_key(&hash) {
+            let (index, refcount) = self.chunk_index.as_mut().unwrap().get_mut(&hash).unwrap();
+            *refcount += 1;
+            *index
+        } else {
+            let index = self.write_blob(&val)?;
+            self.chunk_index.as_mut().unwrap().insert(hash, (index, 1));
+            index
+        };
+
+        let key = key.to_string();
+        self.release_dedup_refs(&key);
+        self.indexes.insert(key.clone(), index);
+        self.key_hashes.as_mut().unwrap().insert(key, hash);
+
+        rewrite_cbor(&self.db_file, &self.indexes)?;
+        rewrite_cbor(self.chunk_file.as_ref().unwrap(), self.chunk_index.as_ref().unwrap())?;
+        rewrite_cbor(self.key_hash_file.as_ref().unwrap(), self.key_hashes.as_ref().unwrap())?;
+        rewrite_cbor(self.manifest_file.as_ref().unwrap(), self.manifests.as_ref().unwrap())?;
+
+        Ok(())
+    }
+
+    /// content-defined-chunking counterpart to `store_deduped`, for blobs large enough that
+    /// storing them whole would re-write the entire value on every small edit. splits `val`
+    /// at content-defined boundaries (see `chunk_boundaries`), content-addresses each chunk
+    /// individually, and records the ordered chunk hashes as the key's manifest.
+    fn store_chunked(&mut self, key: impl ToString, val: &[u8]) -> Result<()> {
+        let mut manifest = Vec::new();
+        let mut start = 0usize;
+
+        for end in chunk_boundaries(val) {
+            let chunk = &val[start..end];
+            let hash = *blake3::hash(chunk).as_bytes();
+
+            if self.chunk_index.as_ref().unwrap().contains_key(&hash) {
+                let (_, refcount) = self.chunk_index.as_mut().unwrap().get_mut(&hash).unwrap();
+                *refcount += 1;
+            } else {
+                let index = self.write_blob(chunk)?;
+                self.chunk_index.as_mut().unwrap().insert(hash, (index, 1));
+            }
+
+            manifest.push(hash);
+            start = end;
+        }
+
+        let key = key.to_string();
+        self.release_dedup_refs(&key);
+        self.indexes.remove(&key);
+        self.manifests.as_mut().unwrap().insert(key, manifest);
+
+        rewrite_cbor(&self.db_file, &self.indexes)?;
+        rewrite_cbor(self.chunk_file.as_ref().unwrap(), self.chunk_index.as_ref().unwrap())?;
+        rewrite_cbor(self.key_hash_file.as_ref().unwrap(), self.key_hashes.as_ref().unwrap())?;
+        rewrite_cbor(self.manifest_file.as_ref().unwrap(), self.manifests.as_ref().unwrap())?;
 
         Ok(())
     }
 
-    /// retrieve a blob from the store
+    /// write `val` into the blob file, reusing a free-list slot if one fits (see
+    /// `best_fit_empty`) and appending otherwise. persists `empties` and returns the
+    /// resulting `Index`, but does not touch `indexes` — callers own that mapping.
+    fn write_blob(&mut self, val: &[u8]) -> Result<Index> {
+        let length = val.len() as u64;
+
+        let offset = if let Some(slot_idx) = self.best_fit_empty(length) {
+            let slot = self.empties.remove(slot_idx);
+
+            let residual = Index {
+                offset: slot.offset + length,
+                length: slot.length - length,
+            };
+            if residual.length >= MIN_FRAGMENT_LENGTH {
+                self.empties.push(residual);
+            }
+
+            self.blob_file.seek(std::io::SeekFrom::Start(slot.offset))?;
+            slot.offset
+        } else {
+            self.blob_file.seek(std::io::SeekFrom::End(0))?
+        };
+
+        self.blob_file.write_all(val)?;
+
+        rewrite_cbor(&self.empty_file, &self.empties)?;
+
+        Ok(Index { offset, length })
+    }
+
+    /// find the smallest free-list slot that can still fit `length` bytes (best-fit), if any.
+    fn best_fit_empty(&self, length: u64) -> Option<usize> {
+        self.empties
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.length >= length)
+            .min_by_key(|(_, slot)| slot.length)
+            .map(|(idx, _)| idx)
+    }
+
+    /// retrieve a blob from the store, erroring if `key` isn't present. see `get` for a
+    /// version that reports an absent key as `Ok(None)` instead.
     pub fn retrieve(&mut self, key: impl ToString) -> Result<Vec<u8>> {
-        let index = self
-            .indexes
-            .get(&key.to_string())
-            .ok_or_else(|| anyhow::anyhow!("key not found"))?;
+        let key = key.to_string();
+        self.get(&key)?
+            .ok_or_else(|| anyhow::anyhow!("key not found"))
+    }
+
+    /// retrieve a blob from the store, returning `Ok(None)` if `key` is absent rather than
+    /// erroring. a damaged region (out of bounds or truncated) is still a genuine `Err`, via
+    /// `StoreError::BlobCorrupt`.
+    pub fn get(&mut self, key: impl ToString) -> Result<Option<Vec<u8>>> {
+        let key = key.to_string();
+
+        if let Some(manifest) = self.manifests.as_ref().and_then(|m| m.get(&key)).cloned() {
+            return Ok(Some(self.retrieve_chunked(&key, &manifest)?));
+        }
+
+        let index = match self.indexes.get(&key) {
+            Some(index) => *index,
+            None => return Ok(None),
+        };
+
+        Ok(Some(self.read_blob(&key, index)?))
+    }
+
+    /// reassembles a blob stored via `store_chunked` by reading each chunk in manifest order.
+    fn retrieve_chunked(&mut self, key: &str, manifest: &[[u8; 32]]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+
+        for hash in manifest {
+            let index = self
+                .chunk_index
+                .as_ref()
+                .unwrap()
+                .get(hash)
+                .map(|(index, _)| *index)
+                .ok_or_else(|| anyhow::anyhow!("chunk missing from chunk_index"))?;
+
+            buf.extend_from_slice(&self.read_blob(key, index)?);
+        }
+
+        Ok(buf)
+    }
+
+    /// reads the region described by `index` out of `mammon_blobs.bin`, surfacing a
+    /// `StoreError::BlobCorrupt` (rather than a raw `read_exact` failure) if it points past
+    /// the end of the file or a short read occurs.
+    fn read_blob(&mut self, key: &str, index: Index) -> Result<Vec<u8>> {
+        let file_len = self.blob_file.metadata()?.len();
+        let out_of_bounds = index
+            .offset
+            .checked_add(index.length)
+            .is_none_or(|end| end > file_len);
+
+        if out_of_bounds {
+            return Err(StoreError::BlobCorrupt {
+                key: key.to_string(),
+                offset: index.offset,
+                length: index.length,
+            }
+            .into());
+        }
 
         self.blob_file
             .seek(std::io::SeekFrom::Start(index.offset))?;
         let mut buf = vec![0; index.length as usize];
-        self.blob_file.read_exact(&mut buf)?;
+        self.blob_file
+            .read_exact(&mut buf)
+            .map_err(|_| StoreError::BlobCorrupt {
+                key: key.to_string(),
+                offset: index.offset,
+                length: index.length,
+            })?;
 
-        return Ok(buf.clone());
+        Ok(buf)
+    }
+
+    /// scans every index (and, if dedup is enabled, every chunk a manifest references) and
+    /// returns the keys whose underlying region is out-of-bounds or truncated, so callers can
+    /// detect damage before relying on the data.
+    pub fn verify(&self) -> Result<Vec<String>> {
+        let file_len = self.blob_file.metadata()?.len();
+        let is_readable =
+            |index: &Index| index.offset.checked_add(index.length).is_some_and(|end| end <= file_len);
+
+        let mut damaged: Vec<String> = self
+            .indexes
+            .iter()
+            .filter(|(_, index)| !is_readable(index))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        if let Some(manifests) = &self.manifests {
+            let chunk_index = self.chunk_index.as_ref().unwrap();
+            for (key, hashes) in manifests {
+                let all_readable = hashes.iter().all(|hash| {
+                    chunk_index
+                        .get(hash)
+                        .map(|(index, _)| is_readable(index))
+                        .unwrap_or(false)
+                });
+
+                if !all_readable {
+                    damaged.push(key.clone());
+                }
+            }
+        }
+
+        Ok(damaged)
     }
 
     /// delete a blob from the store
     pub fn delete(&mut self, key: impl ToString) -> Result<()> {
-        let index = self
+        if self.chunk_index.is_some() {
+            return self.delete_deduped(key);
+        }
+
+        let key = key.to_string();
+        let index = *self
             .indexes
-            .get(&key.to_string())
+            .get(&key)
             .ok_or_else(|| anyhow::anyhow!("key not found"))?;
 
-        self.empties.push(Index {
-            offset: index.offset,
-            length: index.length,
-        }); // sigh emoji
+        self.empties.push(index); // sigh emoji
 
-        self.indexes.remove(&key.to_string());
+        self.indexes.remove(&key);
 
-        ciborium::into_writer(&self.indexes, &self.db_file)?;
-        ciborium::into_writer(&self.empties, &self.empty_file)?;
+        rewrite_cbor(&self.empty_file, &self.empties)?;
+        self.append_wal(&WalRecord::Del { key })?;
+
+        Ok(())
+    }
+
+    /// content-addressed counterpart to `delete`: only reclaims a chunk's region once its
+    /// refcount drops to zero, i.e. once no key references that content anymore. handles
+    /// both whole-blob keys (one hash) and chunked keys (a manifest of hashes).
+    fn delete_deduped(&mut self, key: impl ToString) -> Result<()> {
+        let key = key.to_string();
+
+        let hashes = if let Some(manifest) = self.manifests.as_mut().unwrap().remove(&key) {
+            manifest
+        } else {
+            let hash = self
+                .key_hashes
+                .as_mut()
+                .unwrap()
+                .remove(&key)
+                .ok_or_else(|| anyhow::anyhow!("key not found"))?;
+            vec![hash]
+        };
+
+        self.indexes.remove(&key);
+
+        let chunk_index = self.chunk_index.as_mut().unwrap();
+        for hash in hashes {
+            if let Some((index, refcount)) = chunk_index.get_mut(&hash) {
+                *refcount -= 1;
+                if *refcount == 0 {
+                    self.empties.push(*index);
+                    chunk_index.remove(&hash);
+                }
+            }
+        }
+
+        rewrite_cbor(&self.db_file, &self.indexes)?;
+        rewrite_cbor(&self.empty_file, &self.empties)?;
+        rewrite_cbor(self.chunk_file.as_ref().unwrap(), self.chunk_index.as_ref().unwrap())?;
+        rewrite_cbor(self.key_hash_file.as_ref().unwrap(), self.key_hashes.as_ref().unwrap())?;
+        rewrite_cbor(self.manifest_file.as_ref().unwrap(), self.manifests.as_ref().unwrap())?;
 
         Ok(())
     }
 
-    // FIXME: implement defragmentation, to avoid the file growing forever
+    /// rewrite `mammon_blobs.bin` so that live blobs are packed contiguously, eliminating
+    /// the holes left by `delete()`. a no-op if the store has no free-list slots to reclaim.
+    ///
+    /// this is *not* fully crash-atomic: the rebuilt blob and the checkpoint that describes
+    /// it (new offsets, cleared `empties`, cleared WAL) are staged as temp files and fsynced
+    /// up front, then adopted via a handful of back-to-back renames with the blob renamed
+    /// last — but those renames are still four separate syscalls, not one. a crash between
+    /// them can leave `mammon.cbor` describing the new (compacted) layout while
+    /// `mammon_blobs.bin` is still the old one, or vice versa, so a store that crashed mid-
+    /// `defragment()` should be checked with `verify()` before being trusted. any error during
+    /// staging removes the temp files it had already written rather than leaving them behind.
+    ///
+    /// not supported on a deduped store: it only walks `indexes`, so it would drop
+    /// `chunk_index`'s shared regions (including any referenced only via `manifests`, which
+    /// isn't enumerated here at all) and leave every chunk's recorded offset pointing at the
+    /// old, about-to-be-replaced blob file.
+    pub fn defragment(&mut self) -> Result<()> {
+        if self.chunk_index.is_some() {
+            bail!("defragment() does not support a deduped store");
+        }
+
+        if self.empties.is_empty() {
+            return Ok(());
+        }
+
+        let tmp_paths = [
+            self.directory.join("mammon_blobs.bin.tmp"),
+            self.directory.join("mammon.cbor.tmp"),
+            self.directory.join("mammon_empties.cbor.tmp"),
+            self.directory.join("mammon.wal.tmp"),
+        ];
+
+        let result = self.stage_and_adopt_defragment(&tmp_paths);
+        if result.is_err() {
+            for tmp_path in &tmp_paths {
+                let _ = std::fs::remove_file(tmp_path);
+            }
+        }
+        result
+    }
+
+    fn stage_and_adopt_defragment(&mut self, tmp_paths: &[PathBuf; 4]) -> Result<()> {
+        let [blob_tmp_path, db_tmp_path, empty_tmp_path, wal_tmp_path] = tmp_paths;
+
+        let mut blob_tmp_file = create_file(blob_tmp_path)?;
+
+        let mut entries: Vec<(String, Index)> = self
+            .indexes
+            .iter()
+            .map(|(key, index)| (key.clone(), *index))
+            .collect();
+        entries.sort_by_key(|(_, index)| index.offset);
+
+        let mut rebuilt = HashMap::with_capacity(entries.len());
+        let mut buf = vec![0u8; DEFRAGMENT_CHUNK_SIZE];
+        let mut cursor = 0u64;
+
+        for (key, index) in entries {
+            self.blob_file
+                .seek(std::io::SeekFrom::Start(index.offset))?;
+
+            let mut remaining = index.length;
+            while remaining > 0 {
+                let to_read = remaining.min(DEFRAGMENT_CHUNK_SIZE as u64) as usize;
+                self.blob_file.read_exact(&mut buf[..to_read])?;
+                blob_tmp_file.write_all(&buf[..to_read])?;
+                remaining -= to_read as u64;
+            }
+
+            rebuilt.insert(
+                key,
+                Index {
+                    offset: cursor,
+                    length: index.length,
+                },
+            );
+            cursor += index.length;
+        }
+
+        blob_tmp_file.sync_all()?;
+        drop(blob_tmp_file);
+
+        // stage the checkpoint that matches the rebuilt blob's layout before adopting any of
+        // it, so the adoption below is pure renames with no writes in between.
+        let db_tmp_file = create_file(db_tmp_path)?;
+        rewrite_cbor(&db_tmp_file, &rebuilt)?;
+        db_tmp_file.sync_all()?;
+        drop(db_tmp_file);
+
+        let empty_tmp_file = create_file(empty_tmp_path)?;
+        rewrite_cbor(&empty_tmp_file, &Vec::<Index>::new())?;
+        empty_tmp_file.sync_all()?;
+        drop(empty_tmp_file);
+
+        let wal_tmp_file = create_file(wal_tmp_path)?;
+        wal_tmp_file.sync_all()?;
+        drop(wal_tmp_file);
+
+        let db_path = self.directory.join("mammon.cbor");
+        let empty_path = self.directory.join("mammon_empties.cbor");
+        let wal_path = self.directory.join("mammon.wal");
+        let blob_path = self.directory.join("mammon_blobs.bin");
+
+        std::fs::rename(db_tmp_path, &db_path)?;
+        std::fs::rename(empty_tmp_path, &empty_path)?;
+        std::fs::rename(wal_tmp_path, &wal_path)?;
+        std::fs::rename(blob_tmp_path, &blob_path)?;
+
+        self.db_file = open_file(&db_path)?;
+        self.empty_file = open_file(&empty_path)?;
+        self.wal_file = open_file(&wal_path)?;
+        self.blob_file = open_file(&blob_path)?;
+
+        self.indexes = rebuilt;
+        self.empties.clear();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// a fresh, never-before-used directory under the OS temp dir, so concurrently-run tests
+    /// don't trip over each other's store files or each other's advisory locks.
+    fn test_dir(name: &str) -> PathBuf {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("mammon-test-{}-{n}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn reopens_without_an_explicit_checkpoint() {
+        let dir = test_dir("reopen");
+
+        let mut store = Store::new(dir.clone()).unwrap();
+        store.store("a", b"hello".to_vec()).unwrap();
+        drop(store);
+
+        let mut store = Store::open(dir).unwrap();
+        assert_eq!(store.retrieve("a").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn dedup_reopens_without_an_explicit_checkpoint() {
+        let dir = test_dir("dedup-reopen");
+
+        let mut store = Store::new_deduped(dir.clone()).unwrap();
+        store.store("a", b"hello".to_vec()).unwrap();
+        store.store("b", b"hello".to_vec()).unwrap();
+        drop(store);
+
+        let mut store = Store::open_deduped(dir).unwrap();
+        assert_eq!(store.retrieve("a").unwrap(), b"hello");
+        assert_eq!(store.retrieve("b").unwrap(), b"hello");
+    }
+
+    #[test]
+    fn overwriting_a_deduped_key_releases_its_old_refcount() {
+        let dir = test_dir("dedup-overwrite-refcount");
+        let mut store = Store::new_deduped(dir).unwrap();
+
+        let old_hash = *blake3::hash(b"hello").as_bytes();
+        store.store("a", b"hello".to_vec()).unwrap();
+        assert_eq!(store.chunk_index.as_ref().unwrap().get(&old_hash).unwrap().1, 1);
+
+        store.store("a", b"goodbye".to_vec()).unwrap();
+        assert!(
+            !store.chunk_index.as_ref().unwrap().contains_key(&old_hash),
+            "old content's hash should have been reclaimed once its refcount hit zero"
+        );
+        assert_eq!(store.retrieve("a").unwrap(), b"goodbye");
+    }
+
+    #[test]
+    fn overwriting_a_chunked_key_releases_its_old_manifest_refcounts() {
+        let dir = test_dir("chunked-overwrite-refcount");
+        let mut store = Store::new_deduped(dir).unwrap();
+
+        let first = vec![1u8; CDC_CHUNK_THRESHOLD + 1];
+        let second = vec![2u8; CDC_CHUNK_THRESHOLD + 1];
+
+        store.store("a", first.clone()).unwrap();
+        let first_manifest = store.manifests.as_ref().unwrap().get("a").unwrap().clone();
+        assert!(!first_manifest.is_empty());
+
+        store.store("a", second.clone()).unwrap();
+        for hash in &first_manifest {
+            assert!(
+                !store.chunk_index.as_ref().unwrap().contains_key(hash),
+                "old manifest's chunk hashes should have been reclaimed"
+            );
+        }
+        assert_eq!(store.retrieve("a").unwrap(), second);
+    }
+
+    #[test]
+    fn defragment_refuses_a_deduped_store() {
+        let dir = test_dir("defragment-dedup-guard");
+        let mut store = Store::new_deduped(dir).unwrap();
+        store.store("a", b"hello".to_vec()).unwrap();
+
+        assert!(store.defragment().is_err());
+    }
+
+    #[test]
+    fn defragment_compacts_and_survives_a_reopen() {
+        let dir = test_dir("defragment-reopen");
+
+        let mut store = Store::new(dir.clone()).unwrap();
+        store.store("a", b"hello".to_vec()).unwrap();
+        store.store("b", b"world".to_vec()).unwrap();
+        store.delete("a").unwrap();
+        store.checkpoint().unwrap();
+
+        assert!(!store.empties.is_empty());
+        store.defragment().unwrap();
+        assert!(store.empties.is_empty());
+        assert_eq!(store.retrieve("b").unwrap(), b"world");
+
+        drop(store);
+
+        let mut store = Store::open(dir).unwrap();
+        assert_eq!(store.retrieve("b").unwrap(), b"world");
+        assert!(store.empties.is_empty());
+    }
+
+    #[test]
+    fn open_shared_loads_dedup_sidecars_for_a_deduped_directory() {
+        let dir = test_dir("open-shared-dedup");
+
+        let store = Store::new_deduped(dir.clone()).unwrap();
+        drop(store);
+
+        let shared = Store::open_shared(dir).unwrap();
+        let mut store = shared.lock().unwrap();
+        assert!(store.chunk_index.is_some());
+
+        store.store("a", b"hello".to_vec()).unwrap();
+        assert_eq!(store.retrieve("a").unwrap(), b"hello");
+    }
 }